@@ -1,105 +1,308 @@
 use std::collections::HashMap;
+use std::collections::HashSet;
 
-/// A scoped stack is a stack of hashmaps that allows you to push and pop scopes.
-/// When you push a scope, a new hashmap is created and pushed onto the stack.
-/// When you pop a scope, the top hashmap is popped off the stack.
-/// When you insert a value, it is inserted into the top hashmap.
-/// When you get a value, it is searched for in the top hashmap, and if it is not found, it is searched for in the next hashmap down the stack.
-/// When you remove a value, it is removed from the top hashmap, and if it is not found, it is removed from the next hashmap down the stack.
+mod persistent;
+pub use persistent::PersistentScopedStack;
+
+/// An entry in a `ScopedStack` is either a plain value, or a link to a binding
+/// living in another (usually ancestor) scope level, created via `alias`.
 #[derive(Debug, Clone, PartialEq)]
-pub struct ScopedStack<K, V> where K: std::cmp::Eq + std::hash::Hash {
-  values: HashMap<K, V>,
-  child: Option<Box<ScopedStack<K, V>>>,
+enum Entry<K, V> {
+  Value(V),
+  Link(usize, K),
 }
 
-impl<K, V> ScopedStack<K, V> where K: std::cmp::Eq + std::hash::Hash {
-  /// Creates a new scoped stack.
+/// Error returned by level-indexed operations when the requested scope level doesn't exist.
+/// Levels are addressed from the base scope (`0`) upward; see [`ScopedStack::depth`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfRange;
+
+impl std::fmt::Display for OutOfRange {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "scope level out of range")
+  }
+}
+
+impl std::error::Error for OutOfRange {}
+
+/// A scoped stack is a map that supports pushing and popping lexical scopes.
+/// Internally, each key maps to a stack of entries tagged with the level they were
+/// inserted at (the innermost scope's binding on top), and each open scope records
+/// which keys it introduced. Pushing a scope opens a new recording frame; popping a
+/// scope removes its recorded keys' entries, which automatically restores any binding
+/// that scope had shadowed. This makes `get`/`has` lookups independent of scope depth.
+/// Entries may also be links created via `alias`, letting a key in one scope transparently
+/// read and write a binding living at another level (e.g. for closure capture or `upvar`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScopedStack<K, V> where K: std::cmp::Eq + std::hash::Hash + Clone {
+  values: HashMap<K, Vec<(usize, Entry<K, V>)>>,
+  scopes: Vec<Vec<K>>,
+}
+
+impl<K, V> ScopedStack<K, V> where K: std::cmp::Eq + std::hash::Hash + Clone {
+  /// Creates a new scoped stack with a single (base) scope.
   pub fn new() -> Self {
     ScopedStack {
       values: HashMap::new(),
-      child: None,
+      scopes: vec![Vec::new()],
     }
   }
 
   /// Pushes a new scope onto the stack.
   pub fn push_scope(&mut self) {
-    if let Some(child) = self.child.as_mut() {
-      child.push_scope();
-    } else {
-      self.child = Some(Box::new(ScopedStack::new()));
-    }
+    self.scopes.push(Vec::new());
   }
 
-  /// Pops the top scope off the stack.
+  /// Pops the top scope off the stack, restoring any bindings it shadowed.
+  /// The base scope can never be popped.
   pub fn pop_scope(&mut self) {
-    if let Some(child) = self.child.as_mut() {
-      if child.child.is_some() {
-        child.pop_scope();
-      } else {
-        self.child = None;
+    if self.scopes.len() <= 1 {
+      return;
+    }
+
+    let level = self.scopes.len() - 1;
+    let keys = self.scopes.pop().unwrap();
+
+    for key in keys {
+      if let Some(entries) = self.values.get_mut(&key) {
+        if let Some(pos) = entries.iter().rposition(|(l, _)| *l == level) {
+          entries.remove(pos);
+        }
+        if entries.is_empty() {
+          self.values.remove(&key);
+        }
       }
-    } else {
-      self.child = None;
     }
   }
 
-  /// Inserts a value into the top scope.
+  /// Inserts a value into the top scope. If `key` is an alias, this writes through to
+  /// the aliased binding instead of shadowing it locally.
   pub fn insert(&mut self, key: K, value: V) {
-    if let Some(child) = self.child.as_mut() {
-      child.insert(key, value);
-    } else {
-      self.values.insert(key, value);
+    if let Some((target_level, target_key)) = self.top_link(&key) {
+      if let Some(target) = self.get_at_mut(target_level, &target_key) {
+        *target = value;
+      }
+      return;
+    }
+
+    let level = self.scopes.len() - 1;
+    let entries = self.values.entry(key.clone()).or_insert_with(Vec::new);
+    if let Some(pos) = entries.iter().rposition(|(l, _)| *l == level) {
+      entries[pos].1 = Entry::Value(value);
+      return;
+    }
+
+    entries.push((level, Entry::Value(value)));
+    self.scopes[level].push(key);
+  }
+
+  fn top_link(&self, key: &K) -> Option<(usize, K)> {
+    match self.values.get(key)?.last()? {
+      (_, Entry::Link(level, target_key)) => Some((*level, target_key.clone())),
+      (_, Entry::Value(_)) => None,
     }
   }
 
   /// Inserts a value at the top-most scope where the key already exists (or the bottom scope if it does not exist)
   pub fn insert_existing(&mut self, key: K, value: V) {
-    if let Some(child) = self.child.as_mut() {
-      if child.has(&key) {
-        child.insert_existing(key, value);
-      } else {
-        self.values.insert(key, value);
+    if let Some((target_level, target_key)) = self.top_link(&key) {
+      if let Some(target) = self.get_at_mut(target_level, &target_key) {
+        *target = value;
       }
-    } else {
-      self.values.insert(key, value);
+      return;
     }
+
+    if let Some(top) = self.values.get_mut(&key).and_then(|entries| entries.last_mut()) {
+      top.1 = Entry::Value(value);
+      return;
+    }
+
+    self.values.entry(key.clone()).or_insert_with(Vec::new).push((0, Entry::Value(value)));
+    self.scopes[0].push(key);
   }
 
-  /// Gets a value from the top scope, or any scope below it if it is not found in the top scope.
+  /// Binds `local_key` in the current scope as an alias for `target_key` living at
+  /// `target_level`. Subsequent `get`, `get_mut`, `insert`, and `remove` calls on
+  /// `local_key` transparently read or write through to the aliased binding instead.
+  /// The alias itself is dropped when its owning scope is popped; if `target_level` no
+  /// longer exists by the time it is followed, lookups through the alias return `None`.
+  pub fn alias(&mut self, local_key: K, target_level: usize, target_key: K) {
+    let level = self.scopes.len() - 1;
+    self.values.entry(local_key.clone()).or_insert_with(Vec::new).push((level, Entry::Link(target_level, target_key)));
+    self.scopes[level].push(local_key);
+  }
+
+  /// Gets a value from the top-most scope it is visible in, following aliases.
   pub fn get(&self, key: &K) -> Option<&V> {
-    let mut value = self.values.get(key);
-    let mut child = self.child.as_ref();
-
-    loop {
-      match child {
-        Some(c) => {
-          if let Some(v) = c.values.get(key) {
-            value = Some(v);
-          }
-          child = c.child.as_ref();
-        },
-        None => break,
-      }
+    let level = self.values.get(key)?.last()?.0;
+    self.get_at(level, key)
+  }
+
+  /// Gets a mutable reference to a value from the top-most scope it is visible in, following aliases.
+  pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+    let level = self.values.get(key)?.last()?.0;
+    self.get_at_mut(level, key)
+  }
+
+  /// Gets a value bound directly at a specific scope level, following aliases. Levels are
+  /// addressed from the base scope (`0`) upward; out-of-range levels return `None`.
+  pub fn get_at(&self, level: usize, key: &K) -> Option<&V> {
+    let hops = self.scopes.len();
+    self.get_at_capped(level, key, hops)
+  }
+
+  /// Alias-following `get_at`, with a hop budget so a cycle of mutual aliases fails
+  /// closed (returns `None`) instead of recursing forever. A well-formed chain can
+  /// never be longer than the number of open scopes (aliases commonly reuse the same
+  /// key name at every level, e.g. `upvar x` aliasing local `x` to ancestor `x`, so the
+  /// count of distinct key names is not a safe budget), so depth is used instead.
+  fn get_at_capped(&self, level: usize, key: &K, hops: usize) -> Option<&V> {
+    if level >= self.scopes.len() || hops == 0 {
+      return None;
     }
 
-    value
+    let (_, entry) = self.values.get(key)?.iter().rev().find(|(l, _)| *l == level)?;
+    match entry {
+      Entry::Value(v) => Some(v),
+      Entry::Link(target_level, target_key) => self.get_at_capped(*target_level, target_key, hops - 1),
+    }
   }
 
-  /// Checks if a value exists in the top scope, or any scope below it.
-  pub fn has(&self, key: &K) -> bool {
-    if let Some(child) = self.child.as_ref() {
-      child.has(key)
-    } else {
-      self.values.contains_key(key)
+  /// The number of currently open scopes.
+  pub fn depth(&self) -> usize {
+    self.scopes.len()
+  }
+
+  /// Checks whether a key has a binding recorded directly at a specific scope level.
+  pub fn contains_at(&self, level: usize, key: &K) -> bool {
+    if level >= self.scopes.len() {
+      return false;
+    }
+
+    self.values.get(key).map_or(false, |entries| entries.iter().any(|(l, _)| *l == level))
+  }
+
+  /// Writes into a specific scope level without popping the ones above it, returning the
+  /// value previously bound to `key` at that level, if any. Fails with `OutOfRange` if
+  /// `level` does not name a currently open scope.
+  pub fn insert_at(&mut self, level: usize, key: K, value: V) -> Result<Option<V>, OutOfRange> {
+    if level >= self.scopes.len() {
+      return Err(OutOfRange);
+    }
+
+    let entries = self.values.entry(key.clone()).or_insert_with(Vec::new);
+    if let Some(pos) = entries.iter().rposition(|(l, _)| *l == level) {
+      let previous = std::mem::replace(&mut entries[pos].1, Entry::Value(value));
+      return Ok(match previous {
+        Entry::Value(v) => Some(v),
+        Entry::Link(..) => None,
+      });
     }
+
+    // Entries must stay sorted ascending by level so `.last()` always yields the
+    // topmost binding; insert in place rather than blindly appending.
+    let pos = entries.iter().position(|(l, _)| *l > level).unwrap_or(entries.len());
+    entries.insert(pos, (level, Entry::Value(value)));
+    self.scopes[level].push(key);
+    Ok(None)
+  }
+
+  fn get_at_mut(&mut self, level: usize, key: &K) -> Option<&mut V> {
+    let hops = self.scopes.len();
+    self.get_at_mut_capped(level, key, hops)
+  }
+
+  /// Alias-following `get_at_mut`, with the same hop budget as [`Self::get_at_capped`].
+  fn get_at_mut_capped(&mut self, level: usize, key: &K, hops: usize) -> Option<&mut V> {
+    if level >= self.scopes.len() || hops == 0 {
+      return None;
+    }
+
+    let (pos, link) = {
+      let entries = self.values.get(key)?;
+      let pos = entries.iter().rposition(|(l, _)| *l == level)?;
+      let link = match &entries[pos].1 {
+        Entry::Link(target_level, target_key) => Some((*target_level, target_key.clone())),
+        Entry::Value(_) => None,
+      };
+      (pos, link)
+    };
+
+    match link {
+      Some((target_level, target_key)) => self.get_at_mut_capped(target_level, &target_key, hops - 1),
+      None => match &mut self.values.get_mut(key)?[pos].1 {
+        Entry::Value(v) => Some(v),
+        Entry::Link(..) => None,
+      },
+    }
+  }
+
+  /// Checks if a value exists in any visible scope.
+  pub fn has(&self, key: &K) -> bool {
+    self.get(key).is_some()
+  }
+
+  /// Iterates over every currently-visible `(&K, &V)` binding, following aliases, with
+  /// shadowing resolved: a key shadowed at multiple levels is yielded only once.
+  pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+    self.values.iter().filter_map(|(key, entries)| {
+      let level = entries.last()?.0;
+      self.get_at(level, key).map(|v| (key, v))
+    })
   }
 
-  /// Removes a value from the top scope, or any scope below it if it is not found in the top scope.
+  /// Iterates over the bindings introduced in the current (top) scope only, following aliases.
+  pub fn iter_scope(&self) -> impl Iterator<Item = (&K, &V)> {
+    let level = self.scopes.len() - 1;
+    let mut seen = HashSet::new();
+    self.scopes[level].iter().rev().filter(move |key| seen.insert(*key)).filter_map(move |key| {
+      self.get_at(level, key).map(|v| (key, v))
+    })
+  }
+
+  /// Iterates over every currently-visible binding, allowing values to be mutated in place.
+  /// Bindings whose top-most entry is an alias are skipped, since mutating through an
+  /// alias chain would require a second, overlapping mutable borrow of the same storage.
+  pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+    self.values.iter_mut().filter_map(|(key, entries)| match entries.last_mut() {
+      Some((_, Entry::Value(v))) => Some((key, v)),
+      _ => None,
+    })
+  }
+
+  /// Removes the top-most visible binding for a key, restoring any binding it shadowed.
+  /// If `key` is an alias, this removes the aliased binding rather than the alias itself.
   pub fn remove(&mut self, key: &K) -> Option<V> {
-    if let Some(child) = self.child.as_mut() {
-      child.remove(key)
-    } else {
-      self.values.remove(key)
+    let level = self.values.get(key)?.last()?.0;
+    let hops = self.scopes.len();
+    self.remove_at(level, key, hops)
+  }
+
+  /// Alias-following removal, with the same hop budget as [`Self::get_at_capped`].
+  fn remove_at(&mut self, level: usize, key: &K, hops: usize) -> Option<V> {
+    if level >= self.scopes.len() || hops == 0 {
+      return None;
+    }
+
+    let entries = self.values.get_mut(key)?;
+    let pos = entries.iter().rposition(|(l, _)| *l == level)?;
+    let link = match &entries[pos].1 {
+      Entry::Link(target_level, target_key) => Some((*target_level, target_key.clone())),
+      Entry::Value(_) => None,
+    };
+
+    match link {
+      Some((target_level, target_key)) => self.remove_at(target_level, &target_key, hops - 1),
+      None => {
+        let (_, entry) = entries.remove(pos);
+        if entries.is_empty() {
+          self.values.remove(key);
+        }
+        match entry {
+          Entry::Value(v) => Some(v),
+          Entry::Link(..) => None,
+        }
+      }
     }
   }
 }
@@ -111,51 +314,70 @@ mod tests {
   #[test]
   fn test_new() {
     let stack = ScopedStack::<String, String>::new();
-    assert_eq!(stack.values.len(), 0);
-    assert_eq!(stack.child, None);
+    assert_eq!(stack.get(&"foo".to_string()), None);
   }
 
   #[test]
   fn test_push() {
     let mut stack = ScopedStack::<String, String>::new();
     stack.push_scope();
-    assert_eq!(stack.values.len(), 0);
-    assert_eq!(stack.child.is_some(), true);
+    stack.insert("foo".to_string(), "bar".to_string());
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"bar".to_string()));
   }
 
   #[test]
   fn test_push_scope() {
     let mut stack = ScopedStack::<String, String>::new();
     stack.push_scope();
-    assert_eq!(stack.values.len(), 0);
-    assert_eq!(stack.child.is_some(), true);
+    stack.insert("foo".to_string(), "bar".to_string());
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"bar".to_string()));
   }
 
   #[test]
   fn test_pop() {
     let mut stack = ScopedStack::<String, String>::new();
     stack.push_scope();
+    stack.insert("foo".to_string(), "bar".to_string());
     stack.pop_scope();
-    assert_eq!(stack.values.len(), 0);
-    assert_eq!(stack.child, None);
+    assert_eq!(stack.get(&"foo".to_string()), None);
   }
 
   #[test]
   fn test_pop_scope() {
     let mut stack = ScopedStack::<String, String>::new();
     stack.push_scope();
+    stack.insert("foo".to_string(), "bar".to_string());
     stack.pop_scope();
-    assert_eq!(stack.values.len(), 0);
-    assert_eq!(stack.child, None);
+    assert_eq!(stack.get(&"foo".to_string()), None);
   }
 
   #[test]
   fn test_insert() {
     let mut stack = ScopedStack::<String, String>::new();
     stack.insert("foo".to_string(), "bar".to_string());
-    assert_eq!(stack.values.len(), 1);
-    assert_eq!(stack.values.get("foo"), Some(&"bar".to_string()));
-    assert_eq!(stack.child, None);
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"bar".to_string()));
+  }
+
+  #[test]
+  fn test_insert_reassign_same_scope_overwrites_in_place() {
+    let mut stack = ScopedStack::<String, i32>::new();
+    for i in 0..1000 {
+      stack.insert("x".to_string(), i);
+    }
+
+    assert_eq!(stack.get(&"x".to_string()), Some(&999));
+    assert_eq!(stack.contains_at(0, &"x".to_string()), true);
+  }
+
+  #[test]
+  fn test_insert_reassign_same_scope_then_remove_clears_binding() {
+    let mut stack = ScopedStack::<String, i32>::new();
+    stack.insert("x".to_string(), 1);
+    stack.insert("x".to_string(), 2);
+
+    assert_eq!(stack.remove(&"x".to_string()), Some(2));
+    assert_eq!(stack.has(&"x".to_string()), false);
+    assert_eq!(stack.get(&"x".to_string()), None);
   }
 
   #[test]
@@ -163,10 +385,9 @@ mod tests {
     let mut stack = ScopedStack::<String, String>::new();
     stack.push_scope();
     stack.insert("foo".to_string(), "bar".to_string());
-    assert_eq!(stack.values.len(), 0);
-    assert_eq!(stack.child.is_some(), true);
-    assert_eq!(stack.child.as_ref().unwrap().values.len(), 1);
     assert_eq!(stack.get(&"foo".to_string()), Some(&"bar".to_string()));
+    stack.pop_scope();
+    assert_eq!(stack.get(&"foo".to_string()), None);
   }
 
   #[test]
@@ -176,8 +397,18 @@ mod tests {
     stack.push_scope();
     stack.insert_existing("foo".to_string(), "baz".to_string());
     stack.pop_scope();
-    assert_eq!(stack.values.len(), 1);
-    assert_eq!(stack.child.is_some(), false);
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"baz".to_string()));
+  }
+
+  #[test]
+  fn test_insert_existing_writes_through_alias() {
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+    stack.push_scope();
+    stack.alias("f".to_string(), 0, "foo".to_string());
+    stack.insert_existing("f".to_string(), "baz".to_string());
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"baz".to_string()));
+    stack.pop_scope();
     assert_eq!(stack.get(&"foo".to_string()), Some(&"baz".to_string()));
   }
 
@@ -197,6 +428,14 @@ mod tests {
     assert_eq!(stack.get(&"foo".to_string()), Some(&"baz".to_string()));
   }
 
+  #[test]
+  fn test_get_mut() {
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+    *stack.get_mut(&"foo".to_string()).unwrap() = "baz".to_string();
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"baz".to_string()));
+  }
+
   #[test]
   fn test_has() {
     let mut stack = ScopedStack::<String, String>::new();
@@ -218,7 +457,7 @@ mod tests {
     let mut stack = ScopedStack::<String, String>::new();
     stack.insert("foo".to_string(), "bar".to_string());
     assert_eq!(stack.remove(&"foo".to_string()), Some("bar".to_string()));
-    assert_eq!(stack.values.len(), 0);
+    assert_eq!(stack.has(&"foo".to_string()), false);
   }
 
   #[test]
@@ -228,7 +467,189 @@ mod tests {
     stack.push_scope();
     stack.insert("foo".to_string(), "baz".to_string());
     assert_eq!(stack.remove(&"foo".to_string()), Some("baz".to_string()));
-    assert_eq!(stack.values.len(), 1);
     assert_eq!(stack.get(&"foo".to_string()), Some(&"bar".to_string()));
   }
+
+  #[test]
+  fn test_alias() {
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+    stack.push_scope();
+    stack.alias("f".to_string(), 0, "foo".to_string());
+    assert_eq!(stack.get(&"f".to_string()), Some(&"bar".to_string()));
+  }
+
+  #[test]
+  fn test_alias_write_through() {
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+    stack.push_scope();
+    stack.alias("f".to_string(), 0, "foo".to_string());
+    stack.insert("f".to_string(), "baz".to_string());
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"baz".to_string()));
+    stack.pop_scope();
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"baz".to_string()));
+  }
+
+  #[test]
+  fn test_alias_dropped_with_owning_scope() {
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+    stack.push_scope();
+    stack.alias("f".to_string(), 0, "foo".to_string());
+    stack.pop_scope();
+    assert_eq!(stack.get(&"f".to_string()), None);
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"bar".to_string()));
+  }
+
+  #[test]
+  fn test_alias_to_out_of_range_level_returns_none() {
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.alias("f".to_string(), 5, "foo".to_string());
+    assert_eq!(stack.get(&"f".to_string()), None);
+  }
+
+  #[test]
+  fn test_alias_chain_multiple_hops() {
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+    stack.push_scope();
+    stack.alias("a".to_string(), 0, "foo".to_string());
+    stack.push_scope();
+    stack.alias("b".to_string(), 1, "a".to_string());
+    assert_eq!(stack.get(&"b".to_string()), Some(&"bar".to_string()));
+  }
+
+  #[test]
+  fn test_alias_chain_reusing_same_key_name_upvar_style() {
+    // The canonical `upvar x` case: each scope aliases its own `x` to the ancestor's
+    // `x`, so the chain has only one distinct key name but several hops.
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.insert("x".to_string(), "bar".to_string());
+    stack.push_scope();
+    stack.alias("x".to_string(), 0, "x".to_string());
+    stack.push_scope();
+    stack.alias("x".to_string(), 1, "x".to_string());
+
+    assert_eq!(stack.get(&"x".to_string()), Some(&"bar".to_string()));
+    assert_eq!(stack.get_mut(&"x".to_string()), Some(&mut "bar".to_string()));
+    assert_eq!(stack.remove(&"x".to_string()), Some("bar".to_string()));
+  }
+
+  #[test]
+  fn test_alias_cycle_does_not_overflow_stack() {
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.alias("a".to_string(), 0, "b".to_string());
+    stack.alias("b".to_string(), 0, "a".to_string());
+    assert_eq!(stack.get(&"a".to_string()), None);
+    assert_eq!(stack.get_mut(&"a".to_string()), None);
+    assert_eq!(stack.remove(&"a".to_string()), None);
+  }
+
+  #[test]
+  fn test_iter() {
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+    stack.push_scope();
+    stack.insert("baz".to_string(), "qux".to_string());
+    stack.insert("foo".to_string(), "shadowed".to_string());
+
+    let mut entries: Vec<(&String, &String)> = stack.iter().collect();
+    entries.sort();
+    assert_eq!(entries, vec![
+      (&"baz".to_string(), &"qux".to_string()),
+      (&"foo".to_string(), &"shadowed".to_string()),
+    ]);
+  }
+
+  #[test]
+  fn test_iter_scope() {
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+    stack.push_scope();
+    stack.insert("baz".to_string(), "qux".to_string());
+
+    let entries: Vec<(&String, &String)> = stack.iter_scope().collect();
+    assert_eq!(entries, vec![(&"baz".to_string(), &"qux".to_string())]);
+  }
+
+  #[test]
+  fn test_depth() {
+    let mut stack = ScopedStack::<String, String>::new();
+    assert_eq!(stack.depth(), 1);
+    stack.push_scope();
+    stack.push_scope();
+    assert_eq!(stack.depth(), 3);
+    stack.pop_scope();
+    assert_eq!(stack.depth(), 2);
+  }
+
+  #[test]
+  fn test_get_at() {
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+    stack.push_scope();
+    stack.insert("foo".to_string(), "baz".to_string());
+
+    assert_eq!(stack.get_at(0, &"foo".to_string()), Some(&"bar".to_string()));
+    assert_eq!(stack.get_at(1, &"foo".to_string()), Some(&"baz".to_string()));
+    assert_eq!(stack.get_at(2, &"foo".to_string()), None);
+  }
+
+  #[test]
+  fn test_contains_at() {
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+    stack.push_scope();
+
+    assert_eq!(stack.contains_at(0, &"foo".to_string()), true);
+    assert_eq!(stack.contains_at(1, &"foo".to_string()), false);
+    assert_eq!(stack.contains_at(5, &"foo".to_string()), false);
+  }
+
+  #[test]
+  fn test_insert_at() {
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+    stack.push_scope();
+    stack.push_scope();
+
+    let previous = stack.insert_at(0, "foo".to_string(), "baz".to_string());
+    assert_eq!(previous, Ok(Some("bar".to_string())));
+    assert_eq!(stack.get_at(0, &"foo".to_string()), Some(&"baz".to_string()));
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"baz".to_string()));
+  }
+
+  #[test]
+  fn test_insert_at_out_of_range() {
+    let mut stack = ScopedStack::<String, String>::new();
+    assert_eq!(stack.insert_at(3, "foo".to_string(), "bar".to_string()), Err(OutOfRange));
+  }
+
+  #[test]
+  fn test_insert_at_preserves_level_order_for_lower_level() {
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.push_scope();
+    stack.push_scope();
+    stack.insert("foo".to_string(), "top".to_string());
+
+    let previous = stack.insert_at(0, "foo".to_string(), "bottom".to_string());
+    assert_eq!(previous, Ok(None));
+    // The level-2 binding must still be the one `get`/`get_at` treat as topmost.
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"top".to_string()));
+    assert_eq!(stack.get_at(0, &"foo".to_string()), Some(&"bottom".to_string()));
+    assert_eq!(stack.get_at(2, &"foo".to_string()), Some(&"top".to_string()));
+  }
+
+  #[test]
+  fn test_iter_mut() {
+    let mut stack = ScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+
+    for (_, value) in stack.iter_mut() {
+      value.push_str("!");
+    }
+
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"bar!".to_string()));
+  }
 }
@@ -0,0 +1,147 @@
+use im::HashMap as ImHashMap;
+use im::Vector;
+
+use crate::OutOfRange;
+
+/// A persistent counterpart to [`crate::ScopedStack`], backed by structural-sharing
+/// collections (`im::Vector` of `im::HashMap`s) instead of a regular `Vec`/`HashMap`.
+/// Cloning a `PersistentScopedStack` is O(1) and a clone shares storage with its parent
+/// until one of them is mutated, which makes it cheap to keep many concurrent environment
+/// snapshots around, e.g. one per call frame or speculative branch for backtracking.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PersistentScopedStack<K, V>
+where
+  K: std::cmp::Eq + std::hash::Hash + Clone,
+  V: Clone,
+{
+  scopes: Vector<ImHashMap<K, V>>,
+}
+
+impl<K, V> PersistentScopedStack<K, V>
+where
+  K: std::cmp::Eq + std::hash::Hash + Clone,
+  V: Clone,
+{
+  /// Creates a new persistent scoped stack with a single (base) scope.
+  pub fn new() -> Self {
+    PersistentScopedStack {
+      scopes: Vector::unit(ImHashMap::new()),
+    }
+  }
+
+  /// Pushes a new scope onto the stack.
+  pub fn push_scope(&mut self) {
+    self.scopes.push_back(ImHashMap::new());
+  }
+
+  /// Pops the top scope off the stack. The base scope can never be popped.
+  pub fn pop_scope(&mut self) {
+    if self.scopes.len() > 1 {
+      self.scopes.pop_back();
+    }
+  }
+
+  /// Inserts a value into the top scope.
+  pub fn insert(&mut self, key: K, value: V) {
+    let level = self.scopes.len() - 1;
+    self.scopes[level].insert(key, value);
+  }
+
+  /// Gets a value from the top-most scope it is visible in.
+  pub fn get(&self, key: &K) -> Option<&V> {
+    self.scopes.iter().rev().find_map(|scope| scope.get(key))
+  }
+
+  /// Checks if a value exists in any visible scope.
+  pub fn has(&self, key: &K) -> bool {
+    self.scopes.iter().any(|scope| scope.contains_key(key))
+  }
+
+  /// Removes the top-most visible binding for a key.
+  pub fn remove(&mut self, key: &K) -> Option<V> {
+    for scope in self.scopes.iter_mut().rev() {
+      if let Some(value) = scope.remove(key) {
+        return Some(value);
+      }
+    }
+
+    None
+  }
+
+  /// Writes into a specific scope level without popping the ones above it, returning
+  /// the value it previously held there, if any. Fails with `OutOfRange` if `level`
+  /// does not name a currently open scope.
+  pub fn insert_at(&mut self, level: usize, key: K, value: V) -> Result<Option<V>, OutOfRange> {
+    match self.scopes.get_mut(level) {
+      Some(scope) => Ok(scope.insert(key, value)),
+      None => Err(OutOfRange),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_new() {
+    let stack = PersistentScopedStack::<String, String>::new();
+    assert_eq!(stack.get(&"foo".to_string()), None);
+  }
+
+  #[test]
+  fn test_insert_and_get() {
+    let mut stack = PersistentScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"bar".to_string()));
+  }
+
+  #[test]
+  fn test_push_and_pop_scope() {
+    let mut stack = PersistentScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+    stack.push_scope();
+    stack.insert("foo".to_string(), "baz".to_string());
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"baz".to_string()));
+    stack.pop_scope();
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"bar".to_string()));
+  }
+
+  #[test]
+  fn test_clone_is_independent_snapshot() {
+    let mut stack = PersistentScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+
+    let mut snapshot = stack.clone();
+    snapshot.insert("foo".to_string(), "baz".to_string());
+
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"bar".to_string()));
+    assert_eq!(snapshot.get(&"foo".to_string()), Some(&"baz".to_string()));
+  }
+
+  #[test]
+  fn test_insert_at() {
+    let mut stack = PersistentScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+    stack.push_scope();
+    stack.push_scope();
+
+    let previous = stack.insert_at(0, "foo".to_string(), "baz".to_string());
+    assert_eq!(previous, Ok(Some("bar".to_string())));
+    assert_eq!(stack.get(&"foo".to_string()), Some(&"baz".to_string()));
+  }
+
+  #[test]
+  fn test_insert_at_out_of_range() {
+    let mut stack = PersistentScopedStack::<String, String>::new();
+    assert_eq!(stack.insert_at(3, "foo".to_string(), "bar".to_string()), Err(OutOfRange));
+  }
+
+  #[test]
+  fn test_remove() {
+    let mut stack = PersistentScopedStack::<String, String>::new();
+    stack.insert("foo".to_string(), "bar".to_string());
+    assert_eq!(stack.remove(&"foo".to_string()), Some("bar".to_string()));
+    assert_eq!(stack.has(&"foo".to_string()), false);
+  }
+}